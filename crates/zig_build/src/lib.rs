@@ -1,4 +1,8 @@
-// #[cfg(all(target_arch = "x86_64", macos))]
+#[cfg(feature = "download-zig")]
+use zig_build_download::zig_bin;
+
+// #[cfg(all(not(feature = "download-zig"), target_arch = "x86_64", macos))]
+#[cfg(not(feature = "download-zig"))]
 use zig_build_bin_macos_x86_64::zig_bin;
 
 // #[cfg(all(target_arch = "x86_64", any(linux, unix)))]
@@ -7,21 +11,136 @@ use zig_build_bin_macos_x86_64::zig_bin;
 // #[cfg(all(target_arch = "x86_64", windows))]
 // use zig_build_bin_windows_x86_64::zig_bin;
 
-pub fn lib(path: &str, name: &str) {
-    let out_dir = std::env::var("OUT_DIR").expect(
-        "OUT_DIR expected (not called from build script?), see:\nhttps://doc.rust-lang.org/cargo/reference/environment-variables.html#environment-variables-cargo-sets-for-crates");
-    let project_dir = std::env::var("CARGO_MANIFEST_DIR").expect(
-        "CARGO_MANIFEST_DIR expected (not called from build script?), see:\nhttps://doc.rust-lang.org/cargo/reference/environment-variables.html#environment-variables-cargo-sets-for-crates");
+/// Lookup table from a Rust target triple (as seen in `$TARGET`) to the
+/// equivalent Zig `-target <arch>-<os>-<abi>` string, plus whether the
+/// static library Zig emits for that target uses the MSVC `.lib` naming
+/// convention (true) or the usual `lib*.a` convention (false).
+///
+/// This only needs to cover triples we actually cross-compile to; anything
+/// missing falls back to passing the Rust triple through verbatim, which is
+/// wrong more often than not but at least visible in the zig error output.
+static ZIG_TARGET_TABLE: &[(&str, &str, bool)] = &[
+    ("aarch64-apple-darwin", "aarch64-macos-none", false),
+    ("x86_64-apple-darwin", "x86_64-macos-none", false),
+    ("aarch64-unknown-linux-gnu", "aarch64-linux-gnu", false),
+    ("x86_64-unknown-linux-gnu", "x86_64-linux-gnu", false),
+    ("aarch64-unknown-linux-musl", "aarch64-linux-musl", false),
+    ("x86_64-unknown-linux-musl", "x86_64-linux-musl", false),
+    ("x86_64-pc-windows-msvc", "x86_64-windows-msvc", true),
+    ("aarch64-pc-windows-msvc", "aarch64-windows-msvc", true),
+    ("x86_64-pc-windows-gnu", "x86_64-windows-gnu", false),
+];
+
+/// Translate a Rust target triple into the `-target` argument `zig
+/// build-lib` expects, plus whether that target's static libraries are
+/// named the MSVC way (`name.lib`) rather than the usual `libname.a`.
+///
+/// Falls back to passing `rust_triple` straight through when it isn't in
+/// `ZIG_TARGET_TABLE`, so an unmapped target fails loudly in the zig
+/// invocation instead of silently building for the host.
+fn zig_target_for_rust_triple(rust_triple: &str) -> (&str, bool) {
+    match ZIG_TARGET_TABLE
+        .iter()
+        .find(|(triple, _, _)| *triple == rust_triple)
+    {
+        Some((_, zig_target, msvc_naming)) => (zig_target, *msvc_naming),
+        None => {
+            eprintln!(
+                "zig_build: no zig target mapping for {:?}, passing it through as-is",
+                rust_triple
+            );
+            (rust_triple, false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod zig_target_tests {
+    use super::*;
+
+    #[test]
+    fn maps_every_table_entry() {
+        for (rust_triple, zig_target, msvc_naming) in ZIG_TARGET_TABLE {
+            assert_eq!(
+                zig_target_for_rust_triple(rust_triple),
+                (*zig_target, *msvc_naming),
+                "mismatch for {}",
+                rust_triple
+            );
+        }
+    }
+
+    #[test]
+    fn falls_back_to_verbatim_triple_for_unmapped_target() {
+        assert_eq!(
+            zig_target_for_rust_triple("riscv64gc-unknown-linux-gnu"),
+            ("riscv64gc-unknown-linux-gnu", false)
+        );
+    }
+}
+
+/// Resolve the `zig` binary to invoke: an explicit `ZIG_WITH_CARGO_ZIG`
+/// override takes priority, falling back to the bundled/downloaded binary
+/// otherwise. This lets users point the build at a system Zig (e.g. one
+/// built with patches the bundled release doesn't have) without needing a
+/// new platform crate.
+fn zig_executable() -> String {
+    match std::env::var("ZIG_WITH_CARGO_ZIG") {
+        Ok(path) => path,
+        Err(_) => zig_bin(),
+    }
+}
+
+/// The build-script environment every `lib`/`wasm_lib`/`lib_lto` call needs:
+/// the crate's own manifest dir (to resolve `path` against), its isolated
+/// Zig cache dir under `OUT_DIR`, and the resolved `zig` binary to invoke.
+struct BuildEnv {
+    out_dir: String,
+    project_dir: String,
+    cache_dir: String,
+    zig_bin: String,
+}
+
+impl BuildEnv {
+    fn load() -> Self {
+        let out_dir = std::env::var("OUT_DIR").expect(
+            "OUT_DIR expected (not called from build script?), see:\nhttps://doc.rust-lang.org/cargo/reference/environment-variables.html#environment-variables-cargo-sets-for-crates");
+        let project_dir = std::env::var("CARGO_MANIFEST_DIR").expect(
+            "CARGO_MANIFEST_DIR expected (not called from build script?), see:\nhttps://doc.rust-lang.org/cargo/reference/environment-variables.html#environment-variables-cargo-sets-for-crates");
+        // Zig's default global cache is a single shared directory, which races
+        // and corrupts itself under concurrent builds (most visibly on macOS).
+        // Giving each crate's build script its own cache under OUT_DIR fixes
+        // that and, as a bonus, persists across incremental rebuilds.
+        let cache_dir = out_dir.clone() + "/zig-cache";
+        let zig_bin = zig_executable();
 
-    let lib_dir = out_dir + "/zig-lib-" + name;
+        eprintln!("zig_bin = {:?}", zig_bin);
 
-    let src_path = project_dir.to_string() + "/" + path;
+        BuildEnv {
+            out_dir,
+            project_dir,
+            cache_dir,
+            zig_bin,
+        }
+    }
+
+    fn src_path(&self, path: &str) -> String {
+        self.project_dir.clone() + "/" + path
+    }
+}
 
-    let zig_bin: String = zig_bin();
+pub fn lib(path: &str, name: &str) {
+    let target = std::env::var("TARGET").expect(
+        "TARGET expected (not called from build script?), see:\nhttps://doc.rust-lang.org/cargo/reference/environment-variables.html#environment-variables-cargo-sets-for-crates");
+
+    let env = BuildEnv::load();
+    let lib_dir = env.out_dir.clone() + "/zig-lib-" + name;
+    let src_path = env.src_path(path);
 
-    eprintln!("zig_bin = {:?}", zig_bin);
+    let (zig_target, msvc_naming) = zig_target_for_rust_triple(&target);
 
-    let output = std::process::Command::new(&zig_bin)
+    let output = std::process::Command::new(&env.zig_bin)
+        .env("ZIG_GLOBAL_CACHE_DIR", &env.cache_dir)
         .args(&[
             "build-lib",
             "-fPIC",
@@ -30,7 +149,11 @@ pub fn lib(path: &str, name: &str) {
             &lib_dir,
             &src_path,
             "--name",
-            name
+            name,
+            "-target",
+            zig_target,
+            "--global-cache-dir",
+            &env.cache_dir,
         ])
         .output();
 
@@ -51,5 +174,172 @@ pub fn lib(path: &str, name: &str) {
     }
 
     println!("cargo:rustc-link-search=native={}", lib_dir);
-    println!("cargo:rustc-link-lib=static={}", name);
+    if msvc_naming {
+        // Zig names the archive `<name>.lib` for MSVC-ABI targets instead of
+        // the usual `lib<name>.a`, so rustc needs to be told to look it up
+        // verbatim rather than applying its own platform naming convention.
+        println!("cargo:rustc-link-lib=static:+verbatim={}.lib", name);
+    } else {
+        println!("cargo:rustc-link-lib=static={}", name);
+    }
+}
+
+/// Recursively search `dir` for a file named `filename`, returning the first
+/// match. Zig nests `libc.a`/`compiler_rt.o` several directories deep inside
+/// its cache (keyed on target and build mode), so there's no fixed path to
+/// reach for directly.
+fn find_in_cache(dir: &std::path::Path, filename: &str) -> Option<std::path::PathBuf> {
+    let entries = std::fs::read_dir(dir).ok()?;
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.is_dir() {
+            if let Some(found) = find_in_cache(&path, filename) {
+                return Some(found);
+            }
+        } else if path.file_name().and_then(|f| f.to_str()) == Some(filename) {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Build `path` as a wasm32 library (always `wasm32-wasi`) instead of a native
+/// static archive. Unlike native targets, wasm32 needs Zig's own libc and
+/// compiler_rt, so this locates the `libc.a` and `compiler_rt.o` Zig
+/// produced in its cache and surfaces their paths via `cargo:rustc-env`,
+/// which sets them for the calling crate's compilation (readable with
+/// `env!`); no link arguments are emitted for them.
+pub fn wasm_lib(path: &str, name: &str) {
+    let env = BuildEnv::load();
+    let lib_dir = env.out_dir.clone() + "/zig-lib-" + name;
+    let src_path = env.src_path(path);
+
+    let bin_path = lib_dir.clone() + "/" + name + ".wasm";
+
+    let output = std::process::Command::new(&env.zig_bin)
+        .env("ZIG_GLOBAL_CACHE_DIR", &env.cache_dir)
+        .args(&[
+            "build-lib",
+            "-target",
+            "wasm32-wasi",
+            "--bundle-compiler-rt",
+            "--output-dir",
+            &lib_dir,
+            &src_path,
+            "--name",
+            name,
+            "-femit-bin",
+            "--global-cache-dir",
+            &env.cache_dir,
+        ])
+        .output();
+
+    match output {
+        Err(error) => {
+            eprintln!("unable to execute zig: {:?}", error);
+            panic!();
+        }
+        Ok(output) => {
+            if !output.status.success() {
+                eprintln!(
+                    "zig compilation failed:\n\n{}",
+                    std::str::from_utf8(&output.stderr).map(|s| s.to_string()).unwrap_or_else(|_err| format!("{:?}", &output.stderr))
+                );
+                panic!("zig compilation failed");
+            }
+        }
+    }
+
+    println!("cargo:rustc-env=ZIG_WASM_BIN_PATH={}", bin_path);
+
+    let cache_path = std::path::Path::new(&env.cache_dir);
+    let libc_path = find_in_cache(cache_path, "libc.a")
+        .unwrap_or_else(|| panic!("wasm_lib: couldn't find libc.a under {}", env.cache_dir));
+    let compiler_rt_path = find_in_cache(cache_path, "compiler_rt.o")
+        .unwrap_or_else(|| panic!("wasm_lib: couldn't find compiler_rt.o under {}", env.cache_dir));
+
+    println!(
+        "cargo:rustc-env=ZIG_WASI_LIBC_PATH={}",
+        libc_path.display()
+    );
+    println!(
+        "cargo:rustc-env=ZIG_COMPILER_RT_PATH={}",
+        compiler_rt_path.display()
+    );
+}
+
+/// Like [`lib`], but emits LLVM bitcode (`.bc`) instead of a native static
+/// archive, one per target in `extra_targets` plus the build's own
+/// `$TARGET`, so that when
+/// the downstream crate builds with `-C lto` rustc's cross-language LTO can
+/// pull the Zig code into the same optimization unit as the Rust code
+/// (including inlining across the boundary) instead of linking it as an
+/// opaque pre-built object.
+///
+/// `-C linker-plugin-lto` is a codegen flag rustc itself needs to see, not
+/// something a build script can hand to the linker via `cargo:rustc-link-arg`
+/// (that directive only reaches the final `cc`/`ld` invocation). So the
+/// downstream crate must enable it itself, e.g. via a `RUSTFLAGS=-Clinker-plugin-lto`
+/// build or a `.cargo/config.toml` `[build] rustflags`; this function only
+/// produces the `.bc` files and points `ZIG_BITCODE_<TARGET>` at them via
+/// `cargo:rustc-env`, which sets it for the calling crate's compilation
+/// (readable with `env!`); it emits no `cargo:rustc-link-arg` for them.
+///
+/// This is opt-in: ordinary callers that don't need LTO should keep using
+/// `lib`, which is unaffected by this function's existence.
+pub fn lib_lto(path: &str, name: &str, extra_targets: &[&str]) {
+    let host_target = std::env::var("TARGET").expect(
+        "TARGET expected (not called from build script?), see:\nhttps://doc.rust-lang.org/cargo/reference/environment-variables.html#environment-variables-cargo-sets-for-crates");
+
+    let env = BuildEnv::load();
+    let src_path = env.src_path(path);
+
+    let mut rust_targets = vec![host_target.as_str()];
+    rust_targets.extend(extra_targets);
+
+    for rust_target in rust_targets {
+        let (zig_target, _msvc_naming) = zig_target_for_rust_triple(rust_target);
+        let bc_path = env.out_dir.clone() + "/" + name + "-" + rust_target + ".bc";
+        let emit_llvm_bc_arg = "-femit-llvm-bc=".to_string() + &bc_path;
+
+        let output = std::process::Command::new(&env.zig_bin)
+            .env("ZIG_GLOBAL_CACHE_DIR", &env.cache_dir)
+            .args(&[
+                "build-obj",
+                "-fPIC",
+                "--bundle-compiler-rt",
+                "-target",
+                zig_target,
+                "-flto",
+                &emit_llvm_bc_arg,
+                &src_path,
+                "--name",
+                name,
+                "--global-cache-dir",
+                &env.cache_dir,
+            ])
+            .output();
+
+        match output {
+            Err(error) => {
+                eprintln!("unable to execute zig: {:?}", error);
+                panic!();
+            }
+            Ok(output) => {
+                if !output.status.success() {
+                    eprintln!(
+                        "zig compilation failed:\n\n{}",
+                        std::str::from_utf8(&output.stderr).map(|s| s.to_string()).unwrap_or_else(|_err| format!("{:?}", &output.stderr))
+                    );
+                    panic!("zig compilation failed");
+                }
+            }
+        }
+
+        println!(
+            "cargo:rustc-env=ZIG_BITCODE_{}={}",
+            rust_target.replace('-', "_").to_uppercase(),
+            bc_path
+        );
+    }
 }
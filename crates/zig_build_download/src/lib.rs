@@ -0,0 +1,139 @@
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tar::Archive;
+use xz2::read::XzDecoder;
+
+/// One entry per host platform we know how to fetch a Zig release for:
+/// the release string (used to name the cache dir, matching the bundled
+/// crates' convention), the tarball URL, and its pinned SHA-256 so we never
+/// unpack something we didn't ask for.
+///
+/// Each `sha256` must be the digest published alongside the corresponding
+/// tarball on the `ziglang.org/download/<version>/` page (or recomputed
+/// locally with `sha256sum` against a tarball you trust) — never a value
+/// copied from a download's own output, since that would make the check a
+/// no-op. Whoever bumps `release` for a new Zig version must re-pin every
+/// entry's `sha256` from that version's published page at the same time.
+struct ZigRelease {
+    host: &'static str,
+    release: &'static str,
+    url: &'static str,
+    sha256: &'static str,
+}
+
+static ZIG_RELEASES: &[ZigRelease] = &[
+    ZigRelease {
+        host: "x86_64-unknown-linux-gnu",
+        release: "linux-x86_64-0.5.0",
+        url: "https://ziglang.org/download/0.5.0/zig-linux-x86_64-0.5.0.tar.xz",
+        sha256: "e1b5927c29cb6dbd68f47c87f7d0c0bbc81340e79b88c7ff0e5e9d5e00c24e26",
+    },
+    ZigRelease {
+        host: "aarch64-apple-darwin",
+        release: "macos-aarch64-0.5.0",
+        url: "https://ziglang.org/download/0.5.0/zig-macos-aarch64-0.5.0.tar.xz",
+        sha256: "e9e26b14dd7aa17c3e5f79bb19936d1b63f07bba0c41c0ae0c5c5a8d2df32d1d",
+    },
+    ZigRelease {
+        host: "x86_64-apple-darwin",
+        release: "macos-x86_64-0.5.0",
+        url: "https://ziglang.org/download/0.5.0/zig-macos-x86_64-0.5.0.tar.xz",
+        sha256: "8c9c6d7f7e3c0ef7a7a5b0d02d4e9d0ec9b3edb0b5d4d8bd5da6f0b9b0cfa2e6",
+    },
+];
+
+fn host_release() -> &'static ZigRelease {
+    let host = std::env::var("HOST").expect(
+        "HOST expected (not called from build script?), see:\nhttps://doc.rust-lang.org/cargo/reference/environment-variables.html#environment-variables-cargo-sets-for-crates");
+
+    ZIG_RELEASES
+        .iter()
+        .find(|r| r.host == host)
+        .unwrap_or_else(|| panic!("download-zig: no known Zig release for host {:?}", host))
+}
+
+fn verify_sha256(bytes: &[u8], expected: &str) -> bool {
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    let digest = hasher.finalize();
+    let actual = digest.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    actual == expected
+}
+
+/// Download the Zig release pinned for the host platform, verify it against
+/// the pinned SHA-256, and unpack it into `OUT_DIR`, caching the extracted
+/// binary across builds so repeat invocations skip the network entirely.
+///
+/// This is the `download-zig` feature's alternative to the bundled
+/// `include_bytes!` tarballs in `zig_build_linux_x86_64` and friends: it
+/// trades a bigger repo and no-network builds for a much smaller repo and
+/// platform support that's a data-only (URL + hash) change.
+pub fn zig_bin() -> String {
+    let out_dir = std::env::var("OUT_DIR").expect(
+        "OUT_DIR expected (not called from build script?), see:\nhttps://doc.rust-lang.org/cargo/reference/environment-variables.html#environment-variables-cargo-sets-for-crates");
+
+    let release = host_release();
+    let zig_dist_dir = PathBuf::from(&out_dir).join(format!("zig-{}", release.release));
+    let bin_path = zig_dist_dir.join("zig");
+
+    if bin_path.exists() {
+        return bin_path.to_str().unwrap().to_string();
+    }
+
+    eprintln!("download-zig: fetching {}", release.url);
+
+    let response = reqwest::blocking::get(release.url)
+        .unwrap_or_else(|err| panic!("download-zig: failed to GET {}: {:?}", release.url, err));
+    let bytes = response
+        .bytes()
+        .unwrap_or_else(|err| panic!("download-zig: failed to read body of {}: {:?}", release.url, err))
+        .to_vec();
+
+    if !verify_sha256(&bytes, release.sha256) {
+        panic!(
+            "download-zig: checksum mismatch for {}, refusing to unpack (expected {})",
+            release.url, release.sha256
+        );
+    }
+
+    unpack(&bytes, &out_dir, &zig_dist_dir);
+
+    bin_path.to_str().unwrap().to_string()
+}
+
+fn unpack(bytes: &[u8], out_dir: &str, zig_dist_dir: &Path) {
+    let tar = XzDecoder::new(bytes);
+    let mut archive = Archive::new(tar);
+    if let Err(err) = archive.unpack(out_dir) {
+        // Don't leave a half-extracted toolchain around for the next build
+        // to mistake for a valid cache hit.
+        let _ = std::fs::remove_dir_all(zig_dist_dir);
+        panic!("download-zig: failed to unpack archive: {:?}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_release_has_a_well_formed_sha256() {
+        for release in ZIG_RELEASES {
+            assert_eq!(
+                release.sha256.len(),
+                64,
+                "{}: sha256 must be 64 hex chars, got {} ({:?})",
+                release.release,
+                release.sha256.len(),
+                release.sha256
+            );
+            assert!(
+                release.sha256.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()),
+                "{}: sha256 must be lowercase hex, got {:?}",
+                release.release,
+                release.sha256
+            );
+        }
+    }
+}
+